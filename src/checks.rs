@@ -0,0 +1,807 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use git2::{BranchType, Direction, Oid, Repository, RepositoryState, Status, StatusOptions};
+use globset::GlobSet;
+
+use crate::{remote, scan::DiskUsage, walk};
+
+/// Counts the entries in the stash of the given repository
+pub fn stash_count(repo: &mut Repository) -> Result<usize, git2::Error> {
+    let mut count = 0;
+    repo.stash_foreach(|_, _, _| {
+        count += 1;
+        true
+    })?;
+
+    Ok(count)
+}
+
+/// Lists local branches that have no upstream configured and no remote branch of the same name
+pub fn unpublished_branches(repo: &Repository) -> Result<Vec<String>, git2::Error> {
+    let remotes = repo.remotes()?;
+    let mut unpublished = Vec::new();
+
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        if branch.upstream().is_ok() {
+            continue;
+        }
+
+        let name = String::from_utf8_lossy(branch.name_bytes()?).into_owned();
+        let on_remote = remotes.iter().flatten().any(|remote| {
+            repo.find_reference(&format!("refs/remotes/{remote}/{name}"))
+                .is_ok()
+        });
+
+        if !on_remote {
+            unpublished.push(name);
+        }
+    }
+
+    Ok(unpublished)
+}
+
+/// Checks whether a remote fetches straight into the local branches, like `git clone --mirror` does
+pub fn is_mirror(repo: &Repository) -> Result<bool, git2::Error> {
+    let config = repo.config()?;
+    for name in repo.remotes()?.iter().flatten() {
+        if config
+            .get_bool(&format!("remote.{name}.mirror"))
+            .unwrap_or(false)
+        {
+            return Ok(true);
+        }
+
+        let remote = repo.find_remote(name)?;
+        let fetches_heads = remote.refspecs().any(|spec| {
+            spec.direction() == Direction::Fetch
+                && matches!(spec.dst(), Some("refs/*" | "refs/heads/*"))
+        });
+        if fetches_heads {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Lists tags that do not exist on any of the configured remotes
+///
+/// This connects to every remote of the repository, so it only runs when there are local tags.
+pub fn local_only_tags(repo: &Repository) -> Result<Vec<String>, git2::Error> {
+    let local = repo.tag_names(None)?;
+    if local.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut on_remote = HashSet::new();
+    for name in repo.remotes()?.iter().flatten() {
+        on_remote.extend(remote::remote_tags(repo, name)?);
+    }
+
+    Ok(local
+        .iter()
+        .flatten()
+        .filter(|tag| !on_remote.contains(*tag))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Counts the commits of every local branch that are not on any remote-tracking branch
+///
+/// Branches without such commits are left out.
+pub fn unpushed_commits(repo: &Repository) -> Result<Vec<(String, usize)>, git2::Error> {
+    let remote_tips = branch_tips(repo, BranchType::Remote)?;
+
+    let mut unpushed = Vec::new();
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let Some(oid) = branch.get().target() else {
+            continue;
+        };
+
+        let count = count_commits(repo, oid, &remote_tips)?;
+        if count > 0 {
+            let name = String::from_utf8_lossy(branch.name_bytes()?).into_owned();
+            unpushed.push((name, count));
+        }
+    }
+
+    Ok(unpushed)
+}
+
+/// Counts the commits of every local branch that are not reachable from any of the `advertised` commits
+///
+/// Advertised commits that were never fetched are unknown locally and cannot hide anything, so a
+/// branch can only be verified against a remote state that was fetched before.
+pub fn unverified_branches(
+    repo: &Repository,
+    advertised: &[Oid],
+) -> Result<Vec<(String, usize)>, git2::Error> {
+    let known = known_commits(repo, advertised);
+
+    let mut unverified = Vec::new();
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let Some(oid) = branch.get().target() else {
+            continue;
+        };
+
+        let count = count_commits(repo, oid, &known)?;
+        if count > 0 {
+            let name = String::from_utf8_lossy(branch.name_bytes()?).into_owned();
+            unverified.push((name, count));
+        }
+    }
+
+    Ok(unverified)
+}
+
+/// Counts the commits on a detached HEAD that are neither on a local nor on a remote-tracking branch
+pub fn detached_head_commits(repo: &Repository) -> Result<usize, git2::Error> {
+    if !repo.head_detached()? {
+        return Ok(0);
+    }
+    let Some(head) = repo.head()?.target() else {
+        return Ok(0);
+    };
+
+    let mut known = branch_tips(repo, BranchType::Remote)?;
+    known.extend(branch_tips(repo, BranchType::Local)?);
+    count_commits(repo, head, &known)
+}
+
+/// Counts the commits of every other ref, like notes or tags, that are not on any remote-tracking branch
+///
+/// Local branches and the stash are left out, they are checked on their own. Commits the remotes
+/// `advertised` when they were asked count as pushed as well.
+pub fn unpushed_other_refs(
+    repo: &Repository,
+    advertised: &[Oid],
+) -> Result<Vec<(String, usize)>, git2::Error> {
+    let mut remote_tips = branch_tips(repo, BranchType::Remote)?;
+    remote_tips.extend(known_commits(repo, advertised));
+
+    let mut unpushed = Vec::new();
+    for reference in repo.references()? {
+        let reference = reference?;
+        let name = String::from_utf8_lossy(reference.name_bytes()).into_owned();
+        if ["refs/heads/", "refs/remotes/"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+            || name == "refs/stash"
+        {
+            continue;
+        }
+
+        // Refs can point to trees or blobs as well, those have no history to lose
+        let Ok(commit) = reference.peel_to_commit() else {
+            continue;
+        };
+
+        let count = count_commits(repo, commit.id(), &remote_tips)?;
+        if count > 0 {
+            unpushed.push((name, count));
+        }
+    }
+
+    Ok(unpushed)
+}
+
+/// Counts commits from reflog entries newer than `since` that are not on any branch anymore
+///
+/// These are commits that were reset away or belonged to deleted branches. Reflogs of
+/// remote-tracking refs and the stash are skipped, the stash is checked on its own.
+pub fn orphaned_commits(repo: &Repository, since: DateTime<Utc>) -> Result<usize, git2::Error> {
+    let mut names = vec!["HEAD".to_string()];
+    for reference in repo.references()? {
+        let reference = reference?;
+        let Some(name) = reference.name() else {
+            continue;
+        };
+        if !name.starts_with("refs/remotes/") && name != "refs/stash" {
+            names.push(name.to_string());
+        }
+    }
+
+    let mut revwalk = repo.revwalk()?;
+    for name in &names {
+        for entry in repo.reflog(name)?.iter() {
+            if entry.committer().when().seconds() < since.timestamp() {
+                continue;
+            }
+
+            // Entries can point to commits that were garbage collected already
+            for oid in [entry.id_old(), entry.id_new()] {
+                if !oid.is_zero() && repo.find_commit(oid).is_ok() {
+                    revwalk.push(oid)?;
+                }
+            }
+        }
+    }
+
+    // Work on a detached HEAD is reported on its own
+    let head = repo.head().ok().and_then(|head| head.target());
+    for tip in branch_tips(repo, BranchType::Remote)?
+        .into_iter()
+        .chain(branch_tips(repo, BranchType::Local)?)
+        .chain(head)
+    {
+        revwalk.hide(tip)?;
+    }
+
+    let mut count = 0;
+    for commit in revwalk {
+        commit?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+fn branch_tips(repo: &Repository, kind: BranchType) -> Result<Vec<Oid>, git2::Error> {
+    let mut tips = Vec::new();
+    for branch in repo.branches(Some(kind))? {
+        let (branch, _) = branch?;
+        tips.extend(branch.get().target());
+    }
+
+    Ok(tips)
+}
+
+/// Keeps the advertised oids that are commits in the local object database
+///
+/// Tags are advertised as tag objects next to their peeled commit, and commits that were never fetched are
+/// unknown, neither can be hidden in a revwalk.
+fn known_commits(repo: &Repository, advertised: &[Oid]) -> Vec<Oid> {
+    advertised
+        .iter()
+        .copied()
+        .filter(|oid| repo.find_commit(*oid).is_ok())
+        .collect()
+}
+
+/// Counts the commits reachable from `start` but not from any of the `hidden` commits
+fn count_commits(repo: &Repository, start: Oid, hidden: &[Oid]) -> Result<usize, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(start)?;
+    for oid in hidden {
+        revwalk.hide(*oid)?;
+    }
+
+    let mut count = 0;
+    for commit in revwalk {
+        commit?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Status options that report modified and untracked files, but leave ignored files out
+///
+/// Shared by every check that decides whether a working tree has changes worth keeping.
+pub fn change_status_options() -> StatusOptions {
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false);
+
+    opts
+}
+
+/// Returns the time of the newest commit on any local branch
+pub fn last_commit_time(repo: &Repository) -> Result<Option<DateTime<Utc>>, git2::Error> {
+    let mut newest = None;
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let commit = branch.get().peel_to_commit()?;
+        newest = newest.max(Some(commit.time().seconds()));
+    }
+
+    Ok(newest.and_then(|seconds| DateTime::from_timestamp(seconds, 0)))
+}
+
+/// Returns the most recent sign of activity in the repository
+///
+/// This is the newest of the last commit, the last HEAD reflog entry and the
+/// newest modification time in the working tree.
+pub fn last_activity(
+    repo: &Repository,
+    last_commit: Option<DateTime<Utc>>,
+) -> io::Result<Option<DateTime<Utc>>> {
+    let mut newest = last_commit;
+
+    // Repositories without any commits have no HEAD reflog
+    if let Ok(reflog) = repo.reflog("HEAD") {
+        for entry in reflog.iter() {
+            let when = DateTime::from_timestamp(entry.committer().when().seconds(), 0);
+            newest = newest.max(when);
+        }
+    }
+
+    if let Some(workdir) = repo.workdir() {
+        newest = newest.max(newest_mtime(workdir)?);
+    }
+
+    Ok(newest)
+}
+
+/// Finds the newest modification time of any file in the working tree, ignoring `.git`
+fn newest_mtime(workdir: &Path) -> io::Result<Option<DateTime<Utc>>> {
+    let mut newest = None;
+    let mut pending = vec![workdir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_name() == ".git" {
+                continue;
+            }
+
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                newest = newest.max(Some(DateTime::<Utc>::from(metadata.modified()?)));
+            }
+        }
+    }
+
+    Ok(newest)
+}
+
+/// Measures how much space the git directory, the working tree and its ignored files take up
+pub fn disk_usage(repo: &Repository) -> anyhow::Result<DiskUsage> {
+    let git = walk::dir_size(repo.path(), false)?;
+    let Some(workdir) = repo.workdir() else {
+        return Ok(DiskUsage {
+            git,
+            tracked: 0,
+            ignored: 0,
+        });
+    };
+
+    let mut opts = StatusOptions::new();
+    opts.include_ignored(true)
+        .recurse_ignored_dirs(false)
+        .include_untracked(false);
+
+    let mut ignored = 0;
+    for entry in repo.statuses(Some(&mut opts))?.iter() {
+        if entry.status().contains(Status::IGNORED) {
+            let path = workdir.join(bytes_to_path(entry.path_bytes()));
+            ignored += walk::dir_size(&path, false)?;
+        }
+    }
+
+    let worktree = walk::dir_size(workdir, true)?;
+    Ok(DiskUsage {
+        git,
+        tracked: worktree.saturating_sub(ignored),
+        ignored,
+    })
+}
+
+/// Converts a path reported by git into a native path without losing non UTF-8 names
+#[cfg(unix)]
+pub fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+pub fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Patterns of ignored files that are usually worth keeping, like secrets and local databases
+pub const DEFAULT_PRECIOUS: &[&str] = &[
+    ".env*",
+    "*.sqlite",
+    "*.sqlite3",
+    "*.db",
+    "*.pem",
+    "*.key",
+    "*.local",
+];
+
+/// Lists ignored files that match one of the precious patterns
+///
+/// Patterns are matched against the file name as well as the path relative to the working tree.
+pub fn precious_files(repo: &Repository, patterns: &GlobSet) -> Result<Vec<String>, git2::Error> {
+    if patterns.is_empty() || repo.is_bare() {
+        return Ok(Vec::new());
+    }
+
+    // Ignored files inside untracked directories are only reported when those are walked as well
+    let mut opts = StatusOptions::new();
+    opts.include_ignored(true)
+        .recurse_ignored_dirs(true)
+        .include_untracked(true)
+        .recurse_untracked_dirs(true);
+
+    let mut precious = Vec::new();
+    for entry in repo.statuses(Some(&mut opts))?.iter() {
+        if !entry.status().contains(Status::IGNORED) {
+            continue;
+        }
+
+        let path = bytes_to_path(entry.path_bytes());
+        let name_matches = path.file_name().is_some_and(|name| patterns.is_match(name));
+        if name_matches || patterns.is_match(&path) {
+            precious.push(path.to_string_lossy().into_owned());
+        }
+    }
+
+    Ok(precious)
+}
+
+/// Names of directories that hold build artifacts, and a file that has to exist next to them
+const ARTIFACT_DIRS: &[(&str, Option<&str>)] = &[
+    ("target", Some("Cargo.toml")),
+    ("node_modules", None),
+    (".venv", None),
+    ("venv", None),
+    ("build", None),
+    ("dist", None),
+];
+
+/// Finds ignored directories in the working tree that are known to only contain build artifacts
+pub fn artifact_dirs(repo: &Repository) -> Result<Vec<PathBuf>, git2::Error> {
+    let Some(workdir) = repo.workdir() else {
+        return Ok(Vec::new());
+    };
+
+    // Like for precious files, artifacts inside untracked directories need those to be walked
+    let mut opts = StatusOptions::new();
+    opts.include_ignored(true)
+        .recurse_ignored_dirs(false)
+        .include_untracked(true)
+        .recurse_untracked_dirs(true);
+
+    let mut artifacts = Vec::new();
+    for entry in repo.statuses(Some(&mut opts))?.iter() {
+        // Ignored directories are reported with a trailing slash
+        let relative = bytes_to_path(entry.path_bytes());
+        let path = workdir.join(relative.components().collect::<PathBuf>());
+        if !entry.status().contains(Status::IGNORED) || !path.is_dir() {
+            continue;
+        }
+
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        let is_artifact = ARTIFACT_DIRS.iter().any(|(dir, marker)| {
+            name == *dir && marker.is_none_or(|marker| path.with_file_name(marker).exists())
+        });
+
+        if is_artifact {
+            artifacts.push(path);
+        }
+    }
+
+    Ok(artifacts)
+}
+
+/// Describes an unfinished git operation like a rebase or merge, `None` if there is none
+pub fn operation_in_progress(repo: &Repository) -> Option<&'static str> {
+    let name = match repo.state() {
+        RepositoryState::Clean => {
+            // libgit2 already reports a bisect through the state, checking the log directly as well
+            // keeps the rule independent of how libgit2 detects it
+            if repo.path().join("BISECT_LOG").exists() {
+                "bisect"
+            } else {
+                return None;
+            }
+        }
+        RepositoryState::Merge => "merge",
+        RepositoryState::Revert | RepositoryState::RevertSequence => "revert",
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => "cherry-pick",
+        RepositoryState::Bisect => "bisect",
+        RepositoryState::Rebase | RepositoryState::RebaseMerge => "rebase",
+        RepositoryState::RebaseInteractive => "interactive rebase",
+        RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => "git am",
+    };
+
+    Some(name)
+}
+
+/// Checks the linked worktrees of a main repository for work that would be lost by deleting it
+///
+/// Linked worktrees outside of the repository would also break, because their git directory
+/// lives inside the main repository.
+pub fn worktree_problems(repo: &Repository) -> Result<Vec<String>, git2::Error> {
+    // Bare repositories can have linked worktrees as well
+    let root = repo.workdir().unwrap_or(repo.path());
+    if repo.is_worktree() {
+        return Ok(Vec::new());
+    }
+
+    let mut opts = change_status_options();
+
+    let mut problems = Vec::new();
+    for name in repo.worktrees()?.iter().flatten() {
+        let worktree = repo.find_worktree(name)?;
+        // Stale metadata of a worktree that is gone already, there is nothing to lose
+        if worktree.validate().is_err() {
+            continue;
+        }
+
+        let path = worktree.path().display().to_string();
+        let linked = Repository::open_from_worktree(&worktree)?;
+        let changes = linked.statuses(Some(&mut opts))?.len();
+        if changes > 0 {
+            problems.push(format!("{changes} changed files in worktree {path}"));
+        }
+        if let Some(operation) = operation_in_progress(&linked) {
+            problems.push(format!("{operation} in progress in worktree {path}"));
+        }
+        let detached = detached_head_commits(&linked)?;
+        if detached > 0 {
+            problems.push(format!(
+                "{detached} commits on a detached HEAD in worktree {path}"
+            ));
+        }
+        if !worktree.path().starts_with(root) {
+            problems.push(format!("linked worktree {path} would break"));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Returns the main repository and the name of a linked worktree, `None` for any other directory
+pub fn linked_worktree(path: &Path) -> Option<(PathBuf, String)> {
+    let repo = Repository::open(path).ok()?;
+    if !repo.is_worktree() {
+        return None;
+    }
+
+    // The git directory of a linked worktree is `.git/worktrees/<name>` inside the main repository
+    let name = repo.path().file_name()?.to_string_lossy().into_owned();
+    Some((repo.commondir().to_path_buf(), name))
+}
+
+/// Removes the metadata of a linked worktree whose directory was deleted
+pub fn prune_worktree(commondir: &Path, name: &str) -> Result<(), git2::Error> {
+    let repo = Repository::open(commondir)?;
+    repo.find_worktree(name)?.prune(None)
+}
+
+/// Runs the dirty, stash and unpushed checks against every checked out submodule, recursively
+///
+/// Submodules that were never initialized have nothing local and are skipped.
+pub fn submodule_problems(repo: &Repository) -> Result<Vec<String>, git2::Error> {
+    let mut problems = Vec::new();
+    collect_submodule_problems(repo, "", &mut problems)?;
+
+    Ok(problems)
+}
+
+fn collect_submodule_problems(
+    repo: &Repository,
+    prefix: &str,
+    problems: &mut Vec<String>,
+) -> Result<(), git2::Error> {
+    let mut opts = change_status_options();
+
+    for submodule in repo.submodules()? {
+        let name = format!("{prefix}{}", submodule.path().display());
+        let Ok(mut sub) = submodule.open() else {
+            continue;
+        };
+
+        let changes = sub.statuses(Some(&mut opts))?.len();
+        if changes > 0 {
+            problems.push(format!("submodule {name}: {changes} changed files"));
+        }
+        let stashes = stash_count(&mut sub)?;
+        if stashes > 0 {
+            problems.push(format!("submodule {name}: {stashes} stashed changes"));
+        }
+        for (branch, commits) in unpushed_commits(&sub)? {
+            problems.push(format!(
+                "submodule {name}: {commits} unpushed commits on {branch}"
+            ));
+        }
+        let detached = detached_head_commits(&sub)?;
+        if detached > 0 {
+            problems.push(format!(
+                "submodule {name}: {detached} commits on a detached HEAD"
+            ));
+        }
+
+        collect_submodule_problems(&sub, &format!("{name}/"), problems)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{remote, testutil};
+
+    #[test]
+    fn unpushed_commits_counts_commits_missing_on_remote_tracking_branches() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let repo = testutil::pushed_repo(&dir.path().join("repo"), &url);
+        assert!(unpushed_commits(&repo).unwrap().is_empty());
+
+        testutil::commit(&repo, "second");
+        testutil::commit(&repo, "third");
+        let branch = testutil::current_branch(&repo);
+        assert_eq!(unpushed_commits(&repo).unwrap(), vec![(branch, 2)]);
+    }
+
+    #[test]
+    fn unverified_branches_finds_commits_the_remote_lost() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let repo = testutil::pushed_repo(&dir.path().join("repo"), &url);
+        let first = repo.head().unwrap().target().unwrap();
+        testutil::commit(&repo, "second");
+        testutil::push(&repo);
+
+        let advertised = remote::advertised_oids(&repo, "origin").unwrap();
+        assert!(unverified_branches(&repo, &advertised).unwrap().is_empty());
+
+        // Someone else force-pushes the branch back, the remote-tracking branch still has the commit
+        let branch = testutil::current_branch(&repo);
+        server
+            .reference(&format!("refs/heads/{branch}"), first, true, "force-push")
+            .unwrap();
+        assert!(unpushed_commits(&repo).unwrap().is_empty());
+
+        let advertised = remote::advertised_oids(&repo, "origin").unwrap();
+        assert_eq!(
+            unverified_branches(&repo, &advertised).unwrap(),
+            vec![(branch, 1)]
+        );
+    }
+
+    #[test]
+    fn unverified_branches_ignores_unknown_advertised_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let repo = testutil::pushed_repo(&dir.path().join("repo"), &url);
+        let branch = testutil::current_branch(&repo);
+
+        let unknown = Oid::from_str("0123456789abcdef0123456789abcdef01234567").unwrap();
+        assert_eq!(
+            unverified_branches(&repo, &[unknown]).unwrap(),
+            vec![(branch, 1)]
+        );
+    }
+
+    #[test]
+    fn orphaned_commits_finds_commits_dropped_by_a_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let repo = testutil::pushed_repo(&dir.path().join("repo"), &url);
+        let first = repo.head().unwrap().target().unwrap();
+        testutil::commit(&repo, "second");
+
+        let since = Utc::now() - chrono::TimeDelta::days(1);
+        assert_eq!(orphaned_commits(&repo, since).unwrap(), 0);
+
+        let branch = testutil::current_branch(&repo);
+        repo.reference(&format!("refs/heads/{branch}"), first, true, "reset")
+            .unwrap();
+        assert_eq!(orphaned_commits(&repo, since).unwrap(), 1);
+        assert!(unpushed_commits(&repo).unwrap().is_empty());
+    }
+
+    #[test]
+    fn worktree_problems_reports_changed_and_outside_worktrees() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let repo = testutil::pushed_repo(&dir.path().join("repo"), &url);
+        assert!(worktree_problems(&repo).unwrap().is_empty());
+
+        let path = dir.path().join("linked");
+        repo.worktree("linked", &path, None).unwrap();
+        let problems = worktree_problems(&repo).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].ends_with("would break"));
+
+        std::fs::write(path.join("new.txt"), "new").unwrap();
+        let problems = worktree_problems(&repo).unwrap();
+        assert!(problems[0].starts_with("1 changed files in worktree"));
+
+        // Metadata of a deleted worktree is not worth keeping the repository for
+        std::fs::remove_dir_all(&path).unwrap();
+        assert!(worktree_problems(&repo).unwrap().is_empty());
+    }
+
+    #[test]
+    fn precious_files_matches_file_names_and_relative_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let workdir = repo.workdir().unwrap();
+        fs::write(
+            workdir.join(".gitignore"),
+            "*.log\n.env\n*.sqlite\nconfig/\n",
+        )
+        .unwrap();
+        fs::create_dir_all(workdir.join("app")).unwrap();
+        fs::create_dir_all(workdir.join("config")).unwrap();
+        for file in [
+            "debug.log",
+            "app/.env",
+            "data.sqlite",
+            "config/secrets.json",
+        ] {
+            fs::write(workdir.join(file), "").unwrap();
+        }
+        // Not ignored, so it is reported as a change instead
+        fs::write(workdir.join("local.db"), "").unwrap();
+
+        let mut builder = globset::GlobSetBuilder::new();
+        for pattern in DEFAULT_PRECIOUS.iter().chain(&["config/*.json"]) {
+            builder.add(globset::Glob::new(pattern).unwrap());
+        }
+        let mut precious = precious_files(&repo, &builder.build().unwrap()).unwrap();
+        precious.sort();
+        assert_eq!(
+            precious,
+            vec!["app/.env", "config/secrets.json", "data.sqlite"]
+        );
+
+        assert!(precious_files(&repo, &GlobSet::empty()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn artifact_dirs_requires_a_marker_for_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let workdir = repo.workdir().unwrap().to_path_buf();
+        fs::write(workdir.join(".gitignore"), "target/\nnode_modules/\n").unwrap();
+        for artifact in ["target", "node_modules", "crate/target", "build"] {
+            fs::create_dir_all(workdir.join(artifact)).unwrap();
+            fs::write(workdir.join(artifact).join("file"), "").unwrap();
+        }
+        // `build` is not ignored and `target` has no `Cargo.toml` next to it
+        assert_eq!(
+            artifact_dirs(&repo).unwrap(),
+            vec![workdir.join("node_modules")]
+        );
+
+        fs::write(workdir.join("crate/Cargo.toml"), "").unwrap();
+        let mut artifacts = artifact_dirs(&repo).unwrap();
+        artifacts.sort();
+        assert_eq!(
+            artifacts,
+            vec![workdir.join("crate/target"), workdir.join("node_modules")]
+        );
+    }
+
+    #[test]
+    fn is_mirror_only_matches_mirrors() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let repo = testutil::pushed_repo(&dir.path().join("repo"), &url);
+        assert!(!is_mirror(&repo).unwrap());
+        assert!(!is_mirror(&server).unwrap());
+
+        let mirror = testutil::mirror(&dir.path().join("mirror.git"), &url);
+        assert!(is_mirror(&mirror).unwrap());
+
+        // A mirror refspec alone is enough, even without `remote.<name>.mirror`
+        mirror
+            .config()
+            .unwrap()
+            .remove("remote.origin.mirror")
+            .unwrap();
+        assert!(is_mirror(&mirror).unwrap());
+    }
+}
@@ -1,149 +1,527 @@
-use std::path::PathBuf;
+use std::{cmp::Reverse, fmt, io::IsTerminal, path::PathBuf, sync::Arc, thread, time::Duration};
 
-use clap::Parser;
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
 use colored::Colorize;
-use git2::{Repository, StatusOptions};
-use inquire::{MultiSelect, Select};
-use tokio::fs;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use inquire::{Confirm, MultiSelect, Select};
+use tokio::{fs, sync::Semaphore, task};
+
+use crate::{
+    manifest::{Manifest, ManifestEntry},
+    scan::{Classification, RepoReport, ScanOptions},
+    walk::WalkOptions,
+};
+
+mod checks;
+mod manifest;
+mod quarantine;
+mod remote;
+mod scan;
+#[cfg(test)]
+mod testutil;
+mod walk;
 
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The directory to scan
     #[arg(short, long, default_value = ".")]
     directory: PathBuf,
+
+    /// How many directory levels below the scan directory are searched for repositories
+    #[arg(long, default_value_t = 1)]
+    max_depth: usize,
+
+    /// Also search for repositories nested inside other repositories
+    #[arg(long)]
+    nested: bool,
+
+    /// Require every local branch to have an upstream or a remote branch with the same name
+    #[arg(long)]
+    require_upstream: bool,
+
+    /// Connect to the remotes and keep repositories with tags that were never pushed
+    #[arg(long)]
+    check_tags: bool,
+
+    /// Also keep repositories with unpushed commits on refs other than branches, like notes or tags
+    #[arg(long)]
+    all_refs: bool,
+
+    /// Keep repositories with commits that only survive in reflog entries from this period, e.g. `30d`
+    #[arg(long, value_parser = humantime::parse_duration)]
+    check_reflog: Option<Duration>,
+
+    /// Fetch every remote before checking, repositories whose remotes cannot be reached are kept
+    #[arg(long)]
+    fetch: bool,
+
+    /// Connect to the remotes and keep repositories with branches that are not on any remote ref
+    #[arg(long)]
+    verify_remote: bool,
+
+    /// How long contacting a single remote may take when fetching, verifying or checking tags, e.g. `30s`
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    fetch_timeout: Duration,
+
+    /// Only print the repositories that would be deleted, without prompting or deleting anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Move repositories into a timestamped batch inside this directory instead of deleting them
+    #[arg(long)]
+    quarantine: Option<PathBuf>,
+
+    /// Record the remotes, branch and HEAD of deleted repositories in this JSON file so they can be restored
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// Delete every clean repository without prompting, except those with precious ignored files
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Only offer repositories whose path matches one of these globs, e.g. `**/forks/*`
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// Never offer repositories whose path matches one of these globs
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// How the scan results are printed, structured formats only report and never delete
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// How many repositories are checked in parallel, defaults to the number of CPUs
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Only offer repositories without any commits, checkouts or file changes for this long, e.g. `90d`
+    #[arg(long, value_parser = humantime::parse_duration)]
+    older_than: Option<Duration>,
+
+    /// How the clean repositories are ordered when they are offered for deletion
+    #[arg(long, value_enum, default_value_t = SortBy::Name)]
+    sort: SortBy,
+
+    /// Ignored files matching these globs need an explicit confirmation before their repository is deleted
+    #[arg(long, default_values = checks::DEFAULT_PRECIOUS)]
+    precious: Vec<String>,
+
+    /// Only delete build artifacts like `target/` or `node_modules` instead of whole repositories
+    #[arg(long)]
+    clean_artifacts: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    /// Alphabetically by path
+    Name,
+    /// Largest repositories first
+    Size,
+    /// Repositories that have been idle the longest first
+    Age,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Colored, human readable output followed by the deletion prompt
+    Text,
+    /// A single JSON array with one record per scanned directory
+    Json,
+    /// One JSON record per line
+    Ndjson,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Permanently delete old batches from a quarantine directory
+    Purge {
+        /// The quarantine directory to clean up
+        #[arg(long)]
+        quarantine: PathBuf,
+
+        /// Only delete batches that were quarantined longer ago than this, e.g. `30d`
+        #[arg(long, default_value = "30d", value_parser = humantime::parse_duration)]
+        older_than: Duration,
+    },
+    /// Clone deleted repositories back to their original location
+    Restore {
+        /// The manifest that was written while deleting
+        #[arg(long)]
+        manifest: PathBuf,
+
+        /// The repositories to restore, all missing repositories are offered when empty
+        paths: Vec<PathBuf>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let Args { directory } = Args::parse();
+    let Args {
+        command,
+        directory,
+        max_depth,
+        nested,
+        require_upstream,
+        check_tags,
+        all_refs,
+        check_reflog,
+        fetch,
+        verify_remote,
+        fetch_timeout,
+        dry_run,
+        quarantine: quarantine_dir,
+        manifest,
+        yes,
+        include,
+        exclude,
+        format,
+        jobs,
+        older_than,
+        sort,
+        precious,
+        clean_artifacts,
+    } = Args::parse();
 
-    println!(
-        "{} {}",
-        "Scanning directory".yellow(),
-        directory.display().to_string().yellow()
-    );
-    let mut dirs = fs::read_dir(&directory).await?;
+    if let Some(command) = command {
+        return run_command(command).await;
+    }
 
-    let mut repositories = Vec::new();
-    while let Some(dir) = dirs.next_entry().await? {
-        let path = dir.path();
-        if !path.is_dir() {
-            continue;
-        }
+    let include = (!include.is_empty())
+        .then(|| glob_set(&include))
+        .transpose()?;
+    let exclude = glob_set(&exclude)?;
+    let scan_opts = Arc::new(ScanOptions {
+        require_upstream,
+        check_tags,
+        precious: glob_set(&precious)?,
+        artifacts: clean_artifacts,
+        all_refs,
+        reflog_window: check_reflog,
+        fetch_timeout: fetch.then_some(fetch_timeout),
+        verify_remote,
+    });
+    if fetch || verify_remote || check_tags {
+        remote::set_timeout(fetch_timeout)?;
+    }
+    let noun = if clean_artifacts {
+        "build artifacts"
+    } else {
+        "repositories"
+    };
 
-        let path = path.canonicalize()?;
-        let path_d = path.display().to_string();
-        let repo = Repository::open(&path);
-        if repo.is_err() {
-            println!(
-                "{}{}",
-                path_d.bright_black(),
-                " is not a git repository".bright_black()
-            );
-            continue;
-        }
+    if format == Format::Text {
+        println!(
+            "{} {}",
+            "Scanning directory".yellow(),
+            directory.display().to_string().yellow()
+        );
+    }
+    let mut walk = walk::find_directories(&directory, WalkOptions { max_depth, nested }).await?;
 
-        let repo = repo.unwrap();
-        let mut opts = StatusOptions::new();
-        opts.include_untracked(true)
-            .recurse_untracked_dirs(true)
-            .include_ignored(false);
+    let mut repositories = Vec::new();
+    let mut reports = Vec::new();
+    let mut errored = Vec::new();
+    let mut unreachable = Vec::new();
+    // Every repository that is not deleted, nested repositories keep their parents alive
+    let mut kept = Vec::new();
+    let jobs = jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, usize::from));
+    let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
 
-        let statuses = repo.statuses(Some(&mut opts))?;
+    // Scan in parallel, but collect the results in walk order so the output stays deterministic
+    let handles = walk
+        .directories
+        .into_iter()
+        .map(|path| {
+            let walk_error = walk.errors.remove(&path);
+            let semaphore = semaphore.clone();
+            let scan_opts = scan_opts.clone();
+            let scanned = path.clone();
+            let handle = tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await?;
+                let report = task::spawn_blocking(move || match walk_error {
+                    Some(e) => {
+                        RepoReport::from_error(&path, format!("could not read directory: {e}"))
+                    }
+                    None => match path.canonicalize() {
+                        Ok(path) => scan::scan_directory(&path, &scan_opts),
+                        Err(e) => {
+                            RepoReport::from_error(&path, format!("could not resolve path: {e}"))
+                        }
+                    },
+                })
+                .await?;
+                anyhow::Ok(report)
+            });
+            (scanned, handle)
+        })
+        .collect::<Vec<_>>();
 
-        if statuses.is_empty() {
-            println!("{}{}", "No changes in".green(), path_d.green());
-        } else {
-            println!("{}{}", "Changes in ".red(), path_d.red());
-            continue;
+    for (path, handle) in handles {
+        // A panicking scan only fails its own directory instead of aborting the whole run
+        let report = handle
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|report| report)
+            .unwrap_or_else(|e| RepoReport::from_error(&path, format!("scan failed: {e}")));
+        if !matches!(
+            report.classification,
+            Classification::NotARepo | Classification::Clean
+        ) {
+            kept.push(report.path.clone());
         }
 
-        // Get all local branches
-        let mut revwalk = repo.revwalk()?;
-        for branch in repo.branches(Some(git2::BranchType::Local))? {
-            let (branch, _) = branch?;
-            let target = branch.get().target();
-            if let Some(oid) = target {
-                revwalk.push(oid)?;
+        match report.classification {
+            Classification::Error => errored.push(report.path.clone()),
+            Classification::RemoteUnreachable if !clean_artifacts => {
+                unreachable.push(report.path.clone())
             }
+            _ if clean_artifacts => {
+                // Precious files inside an artifact directory still need a confirmation
+                repositories.extend(report.artifacts.iter().map(|artifact| {
+                    Candidate {
+                        path: artifact.path.clone(),
+                        size: artifact.size,
+                        precious: report
+                            .precious
+                            .iter()
+                            .filter(|file| report.path.join(file).starts_with(&artifact.path))
+                            .cloned()
+                            .collect(),
+                        ..Candidate::from(&report)
+                    }
+                }));
+            }
+            Classification::Clean => repositories.push(Candidate::from(&report)),
+            _ => {}
         }
 
-        // Now exclude all remote branches
-        for branch in repo.branches(Some(git2::BranchType::Remote))? {
-            let (branch, _) = branch?;
-            let target = branch.get().target();
-            if let Some(oid) = target {
-                revwalk.hide(oid)?;
+        match format {
+            Format::Text => print_report(&report),
+            Format::Json | Format::Ndjson => {
+                if format == Format::Ndjson {
+                    println!("{}", serde_json::to_string(&report)?);
+                } else {
+                    reports.push(report);
+                }
             }
         }
+    }
 
-        let mut is_unclean = false;
-        // Iterate over unpushed commits
-        for oid_result in revwalk {
-            let oid = oid_result?;
-            repo.find_commit(oid)?;
+    // Structured output only reports the scan, nothing is deleted
+    match format {
+        Format::Text => {}
+        Format::Json => {
+            println!("{}", serde_json::to_string_pretty(&reports)?);
+            return Ok(());
+        }
+        Format::Ndjson => return Ok(()),
+    }
 
-            is_unclean = true;
-            break;
+    if !errored.is_empty() {
+        println!(
+            "{} {} {}",
+            "Could not check".red(),
+            errored.len().to_string().red(),
+            "directories, they are never deleted:".red()
+        );
+        for path in &errored {
+            println!("{}", path.display().to_string().red());
         }
+    }
 
-        if is_unclean {
-            println!("{}{}", "Unpushed commits in".red(), path_d.red());
-            continue;
+    if !unreachable.is_empty() {
+        println!(
+            "{} {} {}",
+            "Could not fetch the remotes of".yellow(),
+            unreachable.len().to_string().yellow(),
+            "repositories, they are kept until their remotes can be checked:".yellow()
+        );
+        for path in &unreachable {
+            println!("{}", path.display().to_string().yellow());
         }
+    }
 
-        repositories.push(path);
-        println!("{}{}", "Clean repository found:".green(), path_d.green());
+    if let Some(older_than) = older_than {
+        repositories.retain(|candidate| {
+            let keep = candidate.idle().is_none_or(|idle| idle > older_than);
+            if !keep {
+                println!(
+                    "{}{} {}",
+                    "Skipping recently used repository ".bright_black(),
+                    candidate.repo.display().to_string().bright_black(),
+                    format!("({})", candidate.age()).bright_black()
+                );
+                kept.push(candidate.path.clone());
+            }
+            keep
+        });
     }
 
+    repositories.retain(|Candidate { path, repo, .. }| {
+        let keep = include.as_ref().is_none_or(|set| set.is_match(repo)) && !exclude.is_match(repo);
+        if !keep {
+            println!(
+                "{}{}",
+                "Skipping filtered repository ".bright_black(),
+                repo.display().to_string().bright_black()
+            );
+            kept.push(path.clone());
+        }
+        keep
+    });
+
+    // Deleting a directory deletes every nested repository below it as well
+    repositories.retain(|candidate| {
+        let Some(nested) = kept
+            .iter()
+            .find(|path| path.starts_with(&candidate.path) && **path != candidate.path)
+        else {
+            return true;
+        };
+        println!(
+            "{}{}{}{}",
+            "Skipping ".bright_black(),
+            candidate.path.display().to_string().bright_black(),
+            ", it contains the kept repository ".bright_black(),
+            nested.display().to_string().bright_black()
+        );
+        false
+    });
+
     if repositories.is_empty() {
         println!(
             "{}\n{}",
-            "No clean repositories found.".green(),
+            format!("No clean {noun} found.").green(),
             "Exiting".green()
         );
         return Ok(());
     }
 
-    println!("{}", "Found the following clean repositories:".green());
-    for ele in &repositories {
-        println!("{}", ele.display().to_string().green());
+    match sort {
+        SortBy::Name => repositories.sort_by(|a, b| a.path.cmp(&b.path)),
+        SortBy::Size => repositories.sort_by_key(|c| Reverse(c.size)),
+        SortBy::Age => repositories.sort_by_key(|c| c.last_activity),
     }
 
-    let options: Vec<&str> = vec![
-        "Delete all repositories",
-        "Select repositories to delete",
-        "Cancel",
+    // Bare repositories are listed on their own, they are often the only copy on a server
+    let (bare, working): (Vec<_>, Vec<_>) = repositories.iter().partition(|c| c.bare);
+    if !working.is_empty() {
+        println!("{}", format!("Found the following clean {noun}:").green());
+        for candidate in working {
+            println!("{}", candidate.to_string().green());
+        }
+    }
+    if !bare.is_empty() {
+        println!("{}", "Found the following clean bare repositories:".green());
+        for candidate in bare {
+            println!("{}", candidate.to_string().green());
+        }
+    }
+
+    if dry_run {
+        let idle =
+            older_than.map(|d| format!("idle for more than {}", humantime::format_duration(d)));
+
+        println!(
+            "{} {} {}",
+            "Dry run: would delete".yellow(),
+            repositories.len().to_string().yellow(),
+            noun.yellow()
+        );
+        for candidate in &repositories {
+            // Only the checks that actually ran for this repository are listed
+            let mut passed = if clean_artifacts {
+                vec!["ignored build artifacts"]
+            } else {
+                candidate.passed.clone()
+            };
+            passed.extend(idle.as_deref());
+            println!(
+                "{} {}",
+                candidate.to_string().yellow(),
+                format!("({})", passed.join(", ")).bright_black()
+            );
+            if !candidate.precious.is_empty() {
+                println!(
+                    "  {} {}",
+                    "needs confirmation for ignored files:".yellow(),
+                    candidate.precious.join(", ").yellow()
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    let options = vec![
+        format!("Delete all {noun}"),
+        format!("Select {noun} to delete"),
+        "Cancel".to_string(),
     ];
 
-    let ans = Select::new("What do you want to do?", options).prompt()?;
-    if ans == "Cancel" {
+    let ans = if yes {
+        0
+    } else if std::io::stdin().is_terminal() {
+        Select::new("What do you want to do?", options)
+            .raw_prompt()?
+            .index
+    } else {
+        bail!("Not running in a terminal, pass --yes to delete without prompting or --dry-run");
+    };
+    if ans == 2 {
         println!("{}{}", "Cancelled".red(), "Exiting".red());
         return Ok(());
     }
 
-    let repositories = repositories
-        .into_iter()
-        .map(|ele| {
-            ele.to_str()
-                .expect("Invalid UTF-8 in file path")
-                .to_string()
-        })
-        .collect::<Vec<String>>();
-
-    let to_delete = if ans == "Delete all repositories" {
+    // Paths are shown lossily but always deleted by their exact original value
+    let to_delete = if ans == 0 {
         repositories
     } else {
         MultiSelect::new(
-            "Select the repositories that should be deleted",
+            &format!("Select the {noun} that should be deleted"),
             repositories,
         )
         .with_all_selected_by_default()
         .prompt()?
     };
 
+    // Valuable ignored files are never deleted without asking
+    let mut confirmed = Vec::new();
+    for candidate in to_delete {
+        if candidate.precious.is_empty() {
+            confirmed.push(candidate);
+            continue;
+        }
+
+        println!(
+            "{} {} {}",
+            candidate.path.display().to_string().yellow(),
+            "contains ignored files that may be valuable:".yellow(),
+            candidate.precious.join(", ").yellow()
+        );
+        let delete = !yes
+            && Confirm::new("Delete it anyway?")
+                .with_default(false)
+                .prompt()?;
+        if delete {
+            confirmed.push(candidate);
+        } else {
+            println!(
+                "{}{}",
+                "Keeping ".bright_black(),
+                candidate.path.display().to_string().bright_black()
+            );
+        }
+    }
+    let to_delete = confirmed;
+
     if to_delete.is_empty() {
         println!("{}{}", "Cancelled".red(), "Exiting".red());
         return Ok(());
@@ -153,22 +531,312 @@ async fn main() -> anyhow::Result<()> {
         "{} {} {}",
         "Deleting a total of".red(),
         to_delete.len().to_string().red(),
-        "repositories".red()
+        noun.red()
     );
-    for ele in &to_delete {
-        println!("{} {}", "Deleting".red(), ele.red());
-        let path = PathBuf::from(ele);
-        if path.exists() {
-            let e = fs::remove_dir_all(path).await;
-            if e.is_err() {
+    // Build artifacts are regenerated rather than restored, so there is nothing to record
+    if let Some(manifest_path) = manifest.as_ref().filter(|_| !clean_artifacts) {
+        let mut manifest = Manifest::load(manifest_path).await?;
+        for Candidate { path, .. } in &to_delete {
+            manifest.insert(ManifestEntry::record(path)?);
+        }
+
+        manifest.save(manifest_path).await?;
+        println!(
+            "{} {}",
+            "Wrote manifest to".green(),
+            manifest_path.display().to_string().green()
+        );
+    }
+
+    let batch = match &quarantine_dir {
+        Some(dir) => Some(quarantine::create_batch(dir).await?),
+        None => None,
+    };
+
+    let mut freed = 0;
+    for Candidate { path, size, .. } in &to_delete {
+        let path_d = path.display().to_string();
+        let linked = checks::linked_worktree(path);
+        if let Some(batch) = &batch {
+            println!("{} {}", "Quarantining".yellow(), path_d.yellow());
+            if let Err(e) = quarantine::move_into(batch, path).await {
                 println!(
-                    "{} {}",
-                    "Failed to delete".red(),
-                    ele.red()
+                    "{} {} {}",
+                    "Failed to quarantine".red(),
+                    path_d.red(),
+                    format!("({e})").red()
                 );
                 continue;
             }
+        } else {
+            println!("{} {}", "Deleting".red(), path_d.red());
+            if !path.exists() {
+                continue;
+            }
+            let e = fs::remove_dir_all(path).await;
+            if e.is_err() {
+                println!("{} {}", "Failed to delete".red(), path_d.red());
+                continue;
+            }
+            freed += size;
+        }
+
+        // Otherwise the main repository keeps a dangling entry in `.git/worktrees`
+        if let Some((commondir, name)) = linked
+            && let Err(e) = checks::prune_worktree(&commondir, &name)
+        {
+            println!(
+                "{} {} {}",
+                "Failed to prune worktree".red(),
+                name.red(),
+                format!("({})", e.message()).red()
+            );
+        }
+    }
+    if let Some(batch) = &batch {
+        println!(
+            "{} {}",
+            "Quarantined repositories can be found in".green(),
+            batch.display().to_string().green()
+        );
+    } else {
+        println!(
+            "{} {}",
+            "Freed a total of".green(),
+            format_size(freed).green()
+        );
+    }
+    Ok(())
+}
+
+/// A clean repository or build artifact offered for deletion
+struct Candidate {
+    path: PathBuf,
+    /// The repository the candidate belongs to, the same as `path` unless it is a build artifact
+    repo: PathBuf,
+    last_activity: Option<DateTime<Utc>>,
+    /// Total size on disk in bytes
+    size: u64,
+    /// Ignored files that look valuable
+    precious: Vec<String>,
+    bare: bool,
+    /// Descriptions of the checks the repository passed
+    passed: Vec<&'static str>,
+}
+
+impl From<&RepoReport> for Candidate {
+    fn from(report: &RepoReport) -> Self {
+        Self {
+            path: report.path.clone(),
+            repo: report.path.clone(),
+            last_activity: report.last_activity,
+            size: report.size.unwrap_or_default(),
+            precious: report.precious.clone(),
+            bare: report.bare,
+            passed: report.passed.clone(),
+        }
+    }
+}
+
+impl Candidate {
+    /// How long the repository has been idle, `None` if it never had any activity
+    fn idle(&self) -> Option<Duration> {
+        self.last_activity
+            .map(|last| (Utc::now() - last).to_std().unwrap_or_default())
+    }
+
+    fn age(&self) -> String {
+        match self.idle() {
+            Some(idle) => format!("idle for {}", format_age(idle)),
+            None => "never used".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, {}",
+            self.path.display(),
+            format_size(self.size),
+            self.age()
+        )?;
+        if self.bare {
+            write!(f, ", bare")?;
+        }
+        if !self.precious.is_empty() {
+            write!(f, ", {} precious ignored files", self.precious.len())?;
+        }
+
+        write!(f, ")")
+    }
+}
+
+/// Formats a size in bytes using binary units, e.g. `1.5 GiB`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// Formats an age rounded to days, hours or minutes depending on how long it is
+fn format_age(age: Duration) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+
+    let secs = age.as_secs();
+    let unit = match secs {
+        s if s >= DAY => DAY,
+        s if s >= HOUR => HOUR,
+        s if s >= MINUTE => MINUTE,
+        _ => return "less than a minute".to_string(),
+    };
+
+    humantime::format_duration(Duration::from_secs(secs / unit * unit)).to_string()
+}
+
+fn print_report(report: &RepoReport) {
+    let path_d = report.path.display().to_string();
+    let heading = match report.classification {
+        Classification::NotARepo => {
+            println!(
+                "{}{}",
+                path_d.bright_black(),
+                " is not a git repository".bright_black()
+            );
+            return;
+        }
+        Classification::Clean if report.bare => {
+            println!(
+                "{}{}",
+                "Clean bare repository found:".green(),
+                path_d.green()
+            );
+            return;
+        }
+        Classification::Clean => {
+            println!("{}{}", "Clean repository found:".green(), path_d.green());
+            return;
+        }
+        Classification::Dirty => "Changes in",
+        Classification::Unpushed => "Unpushed work in",
+        Classification::Error => "Could not check",
+        Classification::RemoteUnreachable => {
+            println!("{} {}", "Remote unreachable for".yellow(), path_d.yellow());
+            for reason in &report.reasons {
+                println!("  {} {}", "-".yellow(), reason.yellow());
+            }
+            return;
+        }
+    };
+
+    println!("{} {}", heading.red(), path_d.red());
+    for reason in &report.reasons {
+        println!("  {} {}", "-".red(), reason.red());
+    }
+}
+
+/// Compiles glob patterns that are matched against repository paths
+fn glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+
+    builder.build()
+}
+
+async fn run_command(command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Purge {
+            quarantine,
+            older_than,
+        } => {
+            let purged = quarantine::purge(&quarantine, older_than).await?;
+            for batch in &purged {
+                println!("{} {}", "Purged".red(), batch.display().to_string().red());
+            }
+            println!(
+                "{} {} {}",
+                "Purged a total of".green(),
+                purged.len().to_string().green(),
+                "batches".green()
+            );
+        }
+        Command::Restore { manifest, paths } => {
+            let repositories = Manifest::load(&manifest).await?.repositories;
+            let mut failed = 0;
+
+            let to_restore = if paths.is_empty() {
+                let missing = repositories
+                    .into_iter()
+                    .filter(|entry| !entry.path.exists())
+                    .collect::<Vec<_>>();
+                if missing.is_empty() {
+                    println!(
+                        "{}",
+                        "Every repository in the manifest exists already.".green()
+                    );
+                    return Ok(());
+                }
+
+                MultiSelect::new("Select the repositories that should be restored", missing)
+                    .prompt()?
+            } else {
+                // Every requested path is accounted for, nothing is skipped silently
+                let mut to_restore = Vec::new();
+                for path in paths {
+                    let path = std::path::absolute(path)?;
+                    let problem = match repositories.iter().find(|entry| entry.path == path) {
+                        None => "is not in the manifest",
+                        Some(_) if path.exists() => "exists already",
+                        Some(entry) => {
+                            to_restore.push(entry.clone());
+                            continue;
+                        }
+                    };
+                    println!(
+                        "{} {} {}",
+                        "Cannot restore".red(),
+                        path.display().to_string().red(),
+                        problem.red()
+                    );
+                    failed += 1;
+                }
+                to_restore
+            };
+
+            for entry in &to_restore {
+                println!("{} {}", "Restoring".yellow(), entry.to_string().yellow());
+                if let Err(e) = entry.restore() {
+                    println!(
+                        "{} {} {}",
+                        "Failed to restore".red(),
+                        entry.path.display().to_string().red(),
+                        format!("({e})").red()
+                    );
+                    failed += 1;
+                }
+            }
+
+            if failed > 0 {
+                bail!("{failed} repositories could not be restored");
+            }
         }
     }
+
     Ok(())
 }
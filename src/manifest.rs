@@ -0,0 +1,341 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use git2::{Config, FetchOptions, Oid, Repository, build::RepoBuilder};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::fs;
+
+use crate::{checks, remote};
+
+/// Everything needed to clone deleted repositories back to where they were
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub repositories: Vec<ManifestEntry>,
+}
+
+/// The state of a single repository at the time it was deleted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    #[serde(with = "raw_path")]
+    pub path: PathBuf,
+    pub remotes: Vec<RemoteEntry>,
+    /// The checked out branch, `None` if HEAD was detached or unborn
+    pub branch: Option<String>,
+    /// The commit HEAD pointed to, `None` if HEAD was unborn
+    pub head: Option<String>,
+    /// Restored without a working tree
+    #[serde(default)]
+    pub bare: bool,
+    /// Restored as a mirror that fetches every ref of the remote into the same ref
+    #[serde(default)]
+    pub mirror: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteEntry {
+    pub name: String,
+    pub url: String,
+}
+
+impl Manifest {
+    /// Loads the manifest at the given path, returning an empty manifest if it doesn't exist yet
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        if !fs::try_exists(path).await? {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read(path).await?;
+        serde_json::from_slice(&content)
+            .with_context(|| format!("Invalid manifest {}", path.display()))
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        fs::write(path, content).await?;
+
+        Ok(())
+    }
+
+    /// Adds an entry, replacing an older entry for the same path
+    pub fn insert(&mut self, entry: ManifestEntry) {
+        self.repositories.retain(|e| e.path != entry.path);
+        self.repositories.push(entry);
+    }
+}
+
+impl ManifestEntry {
+    /// Records the remotes, branch and HEAD of the repository at the given path
+    pub fn record(path: &Path) -> Result<Self, git2::Error> {
+        let repo = Repository::open(path)?;
+
+        let mut remotes = Vec::new();
+        for name in repo.remotes()?.iter().flatten() {
+            let remote = repo.find_remote(name)?;
+            if let Some(url) = remote.url() {
+                remotes.push(RemoteEntry {
+                    name: name.to_string(),
+                    url: url.to_string(),
+                });
+            }
+        }
+
+        let (branch, head) = match repo.head() {
+            Ok(head) => (
+                head.is_branch()
+                    .then(|| head.shorthand().map(str::to_string))
+                    .flatten(),
+                head.target().map(|oid| oid.to_string()),
+            ),
+            Err(_) => (None, None),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            remotes,
+            branch,
+            head,
+            bare: repo.is_bare(),
+            mirror: repo.is_bare() && checks::is_mirror(&repo)?,
+        })
+    }
+
+    /// Clones the repository back to its original path and checks out the recorded state
+    pub fn restore(&self) -> anyhow::Result<()> {
+        if self.path.exists() {
+            bail!("{} already exists", self.path.display());
+        }
+
+        let Some(origin) = self
+            .remotes
+            .iter()
+            .find(|r| r.name == "origin")
+            .or_else(|| self.remotes.first())
+        else {
+            bail!("{} has no remotes to clone from", self.path.display());
+        };
+
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(remote::callbacks(Config::open_default().ok()));
+
+        let mut builder = RepoBuilder::new();
+        builder.fetch_options(fetch_options);
+        builder.bare(self.bare);
+        builder.remote_create(|repo, _, url| {
+            if !self.mirror {
+                return repo.remote(&origin.name, url);
+            }
+
+            let remote = repo.remote_with_fetch(&origin.name, url, "+refs/*:refs/*")?;
+            repo.config()?
+                .set_bool(&format!("remote.{}.mirror", origin.name), true)?;
+            Ok(remote)
+        });
+        // The branch of a mirror has no remote-tracking branch the clone could look it up in
+        if let Some(branch) = self.branch.as_ref().filter(|_| !self.mirror) {
+            builder.branch(branch);
+        }
+
+        let repo = builder.clone(&origin.url, &self.path)?;
+        if self.mirror {
+            // The clone still points a remote HEAD at a remote-tracking branch a mirror does not have
+            if let Ok(mut remote_head) =
+                repo.find_reference(&format!("refs/remotes/{}/HEAD", origin.name))
+            {
+                remote_head.delete()?;
+            }
+            if let Some(branch) = &self.branch {
+                repo.set_head(&format!("refs/heads/{branch}"))?;
+            }
+        }
+        for remote in self.remotes.iter().filter(|r| r.name != origin.name) {
+            repo.remote(&remote.name, &remote.url)?;
+        }
+
+        let Some(head) = &self.head else {
+            return Ok(());
+        };
+        let expected = Oid::from_str(head)?;
+
+        if self.branch.is_none() {
+            let commit = repo
+                .find_commit(expected)
+                .with_context(|| format!("Recorded HEAD {head} no longer exists on the remote"))?;
+            if !self.bare {
+                repo.checkout_tree(commit.as_object(), None)?;
+            }
+            repo.set_head_detached(expected)?;
+        }
+
+        let actual = repo.head()?.target();
+        if actual != Some(expected) {
+            bail!(
+                "HEAD of {} is {}, expected {head}",
+                self.path.display(),
+                actual.map(|oid| oid.to_string()).unwrap_or_default()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ManifestEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(branch) = &self.branch {
+            write!(f, " ({branch})")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Stores paths as strings, or as raw bytes if they are not valid UTF-8, so every path can be restored exactly
+mod raw_path {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawPath {
+        Utf8(String),
+        Bytes(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(path) => serializer.serialize_str(path),
+            None => serializer.collect_seq(path_bytes(path)),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Ok(match RawPath::deserialize(deserializer)? {
+            RawPath::Utf8(path) => PathBuf::from(path),
+            RawPath::Bytes(bytes) => checks::bytes_to_path(&bytes),
+        })
+    }
+
+    #[cfg(unix)]
+    fn path_bytes(path: &Path) -> Vec<u8> {
+        use std::os::unix::ffi::OsStrExt;
+
+        path.as_os_str().as_bytes().to_vec()
+    }
+
+    #[cfg(not(unix))]
+    fn path_bytes(path: &Path) -> Vec<u8> {
+        path.to_string_lossy().into_owned().into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Repository;
+
+    use super::*;
+    use crate::testutil;
+
+    #[test]
+    fn restore_checks_out_recorded_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let path = dir.path().join("repo");
+        let repo = testutil::pushed_repo(&path, &url);
+        let head = testutil::commit(&repo, "second");
+        testutil::push(&repo);
+        let branch = testutil::current_branch(&repo);
+
+        let entry = ManifestEntry::record(&path).unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+        entry.restore().unwrap();
+
+        let restored = Repository::open(&path).unwrap();
+        assert_eq!(testutil::current_branch(&restored), branch);
+        assert_eq!(restored.head().unwrap().target(), Some(head));
+        assert_eq!(restored.find_remote("origin").unwrap().url(), Some(&*url));
+    }
+
+    #[test]
+    fn restore_detaches_at_recorded_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let path = dir.path().join("repo");
+        let repo = testutil::pushed_repo(&path, &url);
+        let first = repo.head().unwrap().target().unwrap();
+        testutil::commit(&repo, "second");
+        testutil::push(&repo);
+        repo.set_head_detached(first).unwrap();
+
+        let entry = ManifestEntry::record(&path).unwrap();
+        assert_eq!(entry.branch, None);
+        std::fs::remove_dir_all(&path).unwrap();
+        entry.restore().unwrap();
+
+        let restored = Repository::open(&path).unwrap();
+        assert!(restored.head_detached().unwrap());
+        assert_eq!(restored.head().unwrap().target(), Some(first));
+    }
+
+    #[test]
+    fn restore_refuses_existing_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let path = dir.path().join("repo");
+        testutil::pushed_repo(&path, &url);
+
+        let entry = ManifestEntry::record(&path).unwrap();
+        assert!(entry.restore().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths_round_trip() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let mut manifest = Manifest::default();
+        manifest.insert(ManifestEntry {
+            path: Path::new("/tmp").join(OsStr::from_bytes(b"caf\xe9")),
+            remotes: Vec::new(),
+            branch: None,
+            head: None,
+            bare: false,
+            mirror: false,
+        });
+
+        let json = serde_json::to_vec(&manifest).unwrap();
+        let loaded: Manifest = serde_json::from_slice(&json).unwrap();
+        assert_eq!(loaded.repositories[0].path, manifest.repositories[0].path);
+    }
+
+    #[test]
+    fn restore_recreates_bare_repositories_and_mirrors() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let repo = testutil::pushed_repo(&dir.path().join("repo"), &url);
+        let head = repo.head().unwrap().target().unwrap();
+
+        let path = dir.path().join("mirror.git");
+        testutil::mirror(&path, &url);
+        let entry = ManifestEntry::record(&path).unwrap();
+        assert!(entry.bare && entry.mirror);
+        std::fs::remove_dir_all(&path).unwrap();
+        entry.restore().unwrap();
+
+        let restored = Repository::open(&path).unwrap();
+        assert!(restored.is_bare());
+        assert!(checks::is_mirror(&restored).unwrap());
+        let branch = testutil::current_branch(&repo);
+        let local = restored.find_reference(&format!("refs/heads/{branch}"));
+        assert_eq!(local.unwrap().target(), Some(head));
+        assert!(
+            restored
+                .branches(Some(git2::BranchType::Remote))
+                .unwrap()
+                .next()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{NaiveDateTime, Utc};
+use tokio::fs;
+
+/// Format of the timestamped batch directories inside the quarantine directory
+const BATCH_FORMAT: &str = "%Y-%m-%dT%H-%M-%SZ";
+
+/// Creates a new timestamped batch directory inside the quarantine directory
+pub async fn create_batch(quarantine: &Path) -> io::Result<PathBuf> {
+    let batch = quarantine.join(Utc::now().format(BATCH_FORMAT).to_string());
+    fs::create_dir_all(&batch).await?;
+
+    Ok(batch)
+}
+
+/// Moves a repository into the batch directory, keeping its original absolute path below it
+///
+/// Falls back to copying and removing the repository when the batch is on another filesystem.
+pub async fn move_into(batch: &Path, repo: &Path) -> io::Result<PathBuf> {
+    let relative = repo
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect::<PathBuf>();
+
+    let target = batch.join(relative);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    match fs::rename(repo, &target).await {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_dir(repo, &target).await?;
+            fs::remove_dir_all(repo).await?;
+        }
+        res => res?,
+    }
+
+    Ok(target)
+}
+
+/// Recursively copies a directory, recreating symlinks instead of following them
+async fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    let mut pending = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((from, to)) = pending.pop() {
+        fs::create_dir_all(&to).await?;
+
+        let mut entries = fs::read_dir(&from).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            let target = to.join(entry.file_name());
+            if file_type.is_dir() {
+                pending.push((entry.path(), target));
+            } else if file_type.is_symlink() {
+                copy_symlink(&entry.path(), &target).await?;
+            } else {
+                fs::copy(entry.path(), target).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    fs::symlink(fs::read_link(from).await?, to).await
+}
+
+#[cfg(not(unix))]
+async fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to).await.map(|_| ())
+}
+
+/// Removes every batch in the quarantine directory that is older than the given age
+///
+/// Returns the removed batch directories. Entries that were not created by this tool are left alone.
+pub async fn purge(quarantine: &Path, older_than: Duration) -> io::Result<Vec<PathBuf>> {
+    let now = Utc::now().naive_utc();
+    let mut purged = Vec::new();
+
+    let mut entries = fs::read_dir(quarantine).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let Some(created) = name
+            .to_str()
+            .and_then(|name| NaiveDateTime::parse_from_str(name, BATCH_FORMAT).ok())
+        else {
+            continue;
+        };
+
+        let age = (now - created).to_std().unwrap_or_default();
+        if age > older_than {
+            fs::remove_dir_all(entry.path()).await?;
+            purged.push(entry.path());
+        }
+    }
+
+    purged.sort();
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn purge_only_removes_old_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("2020-01-01T00-00-00Z");
+        let foreign = dir.path().join("2020-01-01");
+        fs::create_dir_all(old.join("home/repo")).await.unwrap();
+        fs::create_dir_all(&foreign).await.unwrap();
+        let recent = create_batch(dir.path()).await.unwrap();
+
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert!(purge(dir.path(), 100 * 365 * day).await.unwrap().is_empty());
+        assert!(old.exists());
+
+        let purged = purge(dir.path(), 30 * day).await.unwrap();
+        assert_eq!(purged, vec![old.clone()]);
+        assert!(!old.exists());
+        assert!(foreign.exists());
+        assert!(recent.exists());
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use git2::{
+    Config, Cred, CredentialType, Direction, FetchOptions, FetchPrune, Oid, RemoteCallbacks,
+    Repository,
+};
+
+/// How often authentication is retried before giving up on a remote
+const MAX_AUTH_ATTEMPTS: usize = 3;
+
+/// Builds callbacks that authenticate using the ssh agent or the configured credential helper
+pub fn callbacks(config: Option<Config>) -> RemoteCallbacks<'static> {
+    let mut attempts = 0;
+
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username, allowed| {
+        attempts += 1;
+        if attempts > MAX_AUTH_ATTEMPTS {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+
+        if allowed.contains(CredentialType::SSH_KEY) {
+            return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT)
+            && let Some(config) = &config
+        {
+            return Cred::credential_helper(config, url, username);
+        }
+
+        Cred::default()
+    });
+
+    callbacks
+}
+
+/// Connects to a remote and returns the names of all tags it advertises
+pub fn remote_tags(repo: &Repository, name: &str) -> Result<HashSet<String>, git2::Error> {
+    let mut remote = repo.find_remote(name)?;
+    let connection =
+        remote.connect_auth(Direction::Fetch, Some(callbacks(repo.config().ok())), None)?;
+
+    let tags = connection
+        .list()?
+        .iter()
+        .filter_map(|head| head.name().strip_prefix("refs/tags/"))
+        .map(|tag| tag.trim_end_matches("^{}").to_string())
+        .collect();
+
+    Ok(tags)
+}
+
+/// Connects to a remote and returns the commits of every ref it advertises
+pub fn advertised_oids(repo: &Repository, name: &str) -> Result<Vec<Oid>, git2::Error> {
+    let mut remote = repo.find_remote(name)?;
+    let connection =
+        remote.connect_auth(Direction::Fetch, Some(callbacks(repo.config().ok())), None)?;
+
+    Ok(connection.list()?.iter().map(|head| head.oid()).collect())
+}
+
+/// Limits how long connecting to and waiting for a server may take, for every remote operation
+///
+/// This changes global libgit2 state, so it has to be called before any remote is contacted.
+pub fn set_timeout(timeout: Duration) -> Result<(), git2::Error> {
+    let millis = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+    // SAFETY: called once on startup, before the scan workers touch libgit2
+    unsafe {
+        git2::opts::set_server_connect_timeout_in_milliseconds(millis)?;
+        git2::opts::set_server_timeout_in_milliseconds(millis)?;
+    }
+
+    Ok(())
+}
+
+/// Fetches a remote, pruning remote-tracking branches that were deleted on the server
+///
+/// Remotes that fetch into anything but remote-tracking branches, like the `+refs/*:refs/*` of a
+/// mirror, are left alone, fetching them would overwrite or prune local branches.
+/// Gives up once the transfer takes longer than `timeout`, a server that stops responding is
+/// covered by [`set_timeout`].
+pub fn fetch(repo: &Repository, name: &str, timeout: Duration) -> Result<(), git2::Error> {
+    let mut remote = repo.find_remote(name)?;
+    let only_tracking = remote.refspecs().all(|spec| {
+        spec.direction() != Direction::Fetch
+            || spec
+                .dst()
+                .is_some_and(|dst| dst.starts_with("refs/remotes/"))
+    });
+    if !only_tracking {
+        return Ok(());
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut callbacks = callbacks(repo.config().ok());
+    callbacks.transfer_progress(move |_| Instant::now() < deadline);
+
+    let mut opts = FetchOptions::new();
+    opts.remote_callbacks(callbacks).prune(FetchPrune::On);
+
+    remote
+        .fetch::<&str>(&[], Some(&mut opts), None)
+        .map_err(|e| {
+            if Instant::now() >= deadline {
+                git2::Error::from_str(&format!(
+                    "timed out after {}",
+                    humantime::format_duration(timeout)
+                ))
+            } else {
+                e
+            }
+        })
+}
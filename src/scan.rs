@@ -0,0 +1,702 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use git2::{BranchType, ErrorCode, Repository, Status};
+use globset::GlobSet;
+use serde::{Serialize, Serializer};
+
+use crate::{checks, remote, walk};
+
+/// Which of the optional checks are run for every repository
+#[derive(Debug)]
+pub struct ScanOptions {
+    pub require_upstream: bool,
+    pub check_tags: bool,
+    /// Ignored files matching these patterns need confirmation before they are deleted
+    pub precious: GlobSet,
+    /// Look for build artifacts that can be deleted on their own
+    pub artifacts: bool,
+    /// Also look for unpushed commits on refs other than local branches, like notes or tags
+    pub all_refs: bool,
+    /// Look for orphaned commits in reflog entries that are younger than this
+    pub reflog_window: Option<Duration>,
+    /// Fetch every remote before checking, giving up on a remote after this long
+    pub fetch_timeout: Option<Duration>,
+    /// Ask the remotes which commits they have instead of trusting the remote-tracking branches
+    pub verify_remote: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Classification {
+    NotARepo,
+    Dirty,
+    Unpushed,
+    Clean,
+    Error,
+    /// Looks clean, but a remote could not be fetched so the remote-tracking branches may be stale
+    RemoteUnreachable,
+}
+
+/// A check that was run against a repository
+///
+/// Every check both explains why a repository is kept and describes what passed, so the two cannot drift apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    Changes,
+    Stashes,
+    Operation,
+    Worktrees,
+    Submodules,
+    Unpushed,
+    Verified,
+    DetachedHead,
+    OtherRefs,
+    Reflog,
+    Upstream,
+    Tags,
+    Remotes,
+}
+
+impl Check {
+    /// Describes the check when it found nothing
+    pub fn passed(self) -> &'static str {
+        match self {
+            Check::Changes => "no local changes",
+            Check::Stashes => "no stashes",
+            Check::Operation => "no operation in progress",
+            Check::Worktrees => "no linked worktrees at risk",
+            Check::Submodules => "submodules clean",
+            Check::Unpushed => "all commits pushed",
+            Check::Verified => "branches verified on the remotes",
+            Check::DetachedHead => "no commits on a detached HEAD",
+            Check::OtherRefs => "all refs pushed",
+            Check::Reflog => "no orphaned commits in the reflog",
+            Check::Upstream => "every branch published",
+            Check::Tags => "all tags pushed",
+            Check::Remotes => "remotes reachable",
+        }
+    }
+
+    /// How a repository is classified when the check found something
+    fn failure(self) -> Classification {
+        match self {
+            Check::Changes
+            | Check::Stashes
+            | Check::Operation
+            | Check::Worktrees
+            | Check::Submodules => Classification::Dirty,
+            Check::Unpushed
+            | Check::Verified
+            | Check::DetachedHead
+            | Check::OtherRefs
+            | Check::Reflog
+            | Check::Upstream
+            | Check::Tags => Classification::Unpushed,
+            Check::Remotes => Classification::RemoteUnreachable,
+        }
+    }
+}
+
+/// Commits of a local branch that are not on any remote-tracking branch
+#[derive(Debug, Serialize)]
+pub struct UnpushedBranch {
+    pub branch: String,
+    pub commits: usize,
+}
+
+/// An ignored directory with build artifacts
+#[derive(Debug, Serialize)]
+pub struct Artifact {
+    #[serde(serialize_with = "serialize_lossy")]
+    pub path: PathBuf,
+    /// Size on disk in bytes
+    pub size: u64,
+}
+
+/// Space taken up by a repository, in bytes
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DiskUsage {
+    /// The git directory with the object database
+    pub git: u64,
+    /// Files in the working tree that are not ignored
+    pub tracked: u64,
+    /// Ignored files like build artifacts
+    pub ignored: u64,
+}
+
+impl DiskUsage {
+    pub fn total(&self) -> u64 {
+        self.git + self.tracked + self.ignored
+    }
+}
+
+/// Commits of some other ref that are not on any remote-tracking branch
+#[derive(Debug, Serialize)]
+pub struct UnpushedRef {
+    pub reference: String,
+    pub commits: usize,
+}
+
+/// The result of every check that was run against a single directory
+#[derive(Debug, Serialize)]
+pub struct RepoReport {
+    #[serde(serialize_with = "serialize_lossy")]
+    pub path: PathBuf,
+    pub classification: Classification,
+    /// Bare repositories have no working tree, so only their refs are checked
+    pub bare: bool,
+    /// A bare repository whose remote fetches straight into its branches, like `git clone --mirror`
+    pub mirror: bool,
+    /// A bare clone or mirror without remote-tracking branches, only `--verify-remote` can check its branches
+    pub needs_verification: bool,
+    /// Human readable summary of everything that prevents deletion
+    pub reasons: Vec<String>,
+    /// Tracked files with staged or unstaged changes
+    pub modified: Vec<String>,
+    pub untracked: Vec<String>,
+    pub stashes: usize,
+    /// An unfinished operation like a rebase, merge or bisect
+    pub operation: Option<String>,
+    /// Linked worktrees with changes, or that would break when the repository is deleted
+    pub worktrees: Vec<String>,
+    /// Submodules with changes, stashes or unpushed commits
+    pub submodules: Vec<String>,
+    pub unpushed: Vec<UnpushedBranch>,
+    /// Commits of local branches that none of the remotes advertises, only checked when verifying remotes
+    pub unverified: Vec<UnpushedBranch>,
+    /// Commits on a detached HEAD that are not on any branch
+    pub detached_commits: usize,
+    pub unpushed_refs: Vec<UnpushedRef>,
+    /// Commits from recent reflog entries that are not on any branch anymore
+    pub orphaned_commits: usize,
+    pub unpublished_branches: Vec<String>,
+    pub local_only_tags: Vec<String>,
+    /// Ignored files that look valuable, like `.env` files or local databases
+    pub precious: Vec<String>,
+    /// Build artifacts, only collected when looking for them
+    pub artifacts: Vec<Artifact>,
+    /// Checks that could not be completed
+    pub errors: Vec<String>,
+    /// Remotes that could not be fetched, together with the reason
+    pub unreachable_remotes: Vec<String>,
+    /// Total size on disk in bytes
+    pub size: Option<u64>,
+    pub disk_usage: Option<DiskUsage>,
+    pub last_commit: Option<DateTime<Utc>>,
+    /// The newest of the last commit, the last HEAD reflog entry and the newest file in the working tree
+    pub last_activity: Option<DateTime<Utc>>,
+    /// The checks that were run, in the order their results are reported
+    #[serde(skip)]
+    pub checks: Vec<Check>,
+    /// Descriptions of the checks that found nothing
+    pub passed: Vec<&'static str>,
+}
+
+impl RepoReport {
+    fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            classification: Classification::NotARepo,
+            bare: false,
+            mirror: false,
+            needs_verification: false,
+            reasons: Vec::new(),
+            modified: Vec::new(),
+            untracked: Vec::new(),
+            stashes: 0,
+            operation: None,
+            worktrees: Vec::new(),
+            submodules: Vec::new(),
+            unpushed: Vec::new(),
+            unverified: Vec::new(),
+            detached_commits: 0,
+            unpushed_refs: Vec::new(),
+            orphaned_commits: 0,
+            unpublished_branches: Vec::new(),
+            local_only_tags: Vec::new(),
+            precious: Vec::new(),
+            artifacts: Vec::new(),
+            errors: Vec::new(),
+            unreachable_remotes: Vec::new(),
+            size: None,
+            disk_usage: None,
+            last_commit: None,
+            last_activity: None,
+            checks: Vec::new(),
+            passed: Vec::new(),
+        }
+    }
+
+    /// Creates the report of a directory that could not be checked at all
+    pub fn from_error(path: &Path, error: String) -> Self {
+        Self {
+            errors: vec![error],
+            ..Self::new(path)
+        }
+        .finish()
+    }
+
+    /// Derives the classification, the reasons and the passed checks from the collected check results
+    fn finish(mut self) -> Self {
+        let mut reasons = Vec::new();
+        let mut failures = Vec::new();
+        let mut passed = Vec::new();
+        for &check in &self.checks {
+            let found = self.findings(check);
+            if found.is_empty() {
+                passed.push(check.passed());
+            } else {
+                reasons.extend(found);
+                failures.push(check.failure());
+            }
+        }
+        reasons.extend(self.errors.iter().cloned());
+
+        self.classification = if failures.contains(&Classification::Dirty) {
+            Classification::Dirty
+        } else if failures.contains(&Classification::Unpushed) {
+            Classification::Unpushed
+        } else if !self.errors.is_empty() {
+            Classification::Error
+        } else if failures.contains(&Classification::RemoteUnreachable) {
+            Classification::RemoteUnreachable
+        } else {
+            Classification::Clean
+        };
+        self.reasons = reasons;
+        self.passed = passed;
+        self
+    }
+
+    /// Human readable descriptions of everything a check found, empty if it passed
+    fn findings(&self, check: Check) -> Vec<String> {
+        let mut found = Vec::new();
+        match check {
+            Check::Changes => {
+                if !self.modified.is_empty() {
+                    found.push(format!("{} modified files", self.modified.len()));
+                }
+                if !self.untracked.is_empty() {
+                    found.push(format!("{} untracked files", self.untracked.len()));
+                }
+            }
+            Check::Stashes if self.stashes > 0 => {
+                found.push(format!("{} stashed changes", self.stashes));
+            }
+            Check::Operation => {
+                found.extend(
+                    self.operation
+                        .iter()
+                        .map(|operation| format!("{operation} in progress")),
+                );
+            }
+            Check::Worktrees => found.extend(self.worktrees.iter().cloned()),
+            Check::Submodules => found.extend(self.submodules.iter().cloned()),
+            Check::Unpushed => {
+                for UnpushedBranch { branch, commits } in &self.unpushed {
+                    found.push(format!("{commits} unpushed commits on {branch}"));
+                }
+                if self.needs_verification {
+                    let kind = if self.mirror { "mirror" } else { "bare clone" };
+                    found.push(format!(
+                        "{kind} without remote-tracking branches, pass --verify-remote to check it against its remotes"
+                    ));
+                }
+            }
+            Check::Verified => {
+                for UnpushedBranch { branch, commits } in &self.unverified {
+                    found.push(format!(
+                        "{commits} commits on {branch} are missing on the remotes"
+                    ));
+                }
+            }
+            Check::DetachedHead if self.detached_commits > 0 => {
+                found.push(format!(
+                    "{} commits on a detached HEAD that are not on any branch",
+                    self.detached_commits
+                ));
+            }
+            Check::OtherRefs => {
+                for UnpushedRef { reference, commits } in &self.unpushed_refs {
+                    found.push(format!("{commits} unpushed commits on {reference}"));
+                }
+            }
+            Check::Reflog if self.orphaned_commits > 0 => {
+                found.push(format!(
+                    "{} orphaned commits only reachable from the reflog",
+                    self.orphaned_commits
+                ));
+            }
+            Check::Upstream if !self.unpublished_branches.is_empty() => {
+                found.push(format!(
+                    "branches never pushed: {}",
+                    self.unpublished_branches.join(", ")
+                ));
+            }
+            Check::Tags if !self.local_only_tags.is_empty() => {
+                found.push(format!(
+                    "tags never pushed: {}",
+                    self.local_only_tags.join(", ")
+                ));
+            }
+            Check::Remotes => {
+                for remote in &self.unreachable_remotes {
+                    found.push(format!("remote unreachable: {remote}"));
+                }
+            }
+            _ => {}
+        }
+
+        found
+    }
+}
+
+/// Serializes paths that are not valid UTF-8 lossily instead of failing
+fn serialize_lossy<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+/// Runs every check against the directory at `path` to find out whether it can be deleted
+///
+/// Errors never abort the scan, they classify the directory as [`Classification::Error`] instead.
+pub fn scan_directory(path: &Path, opts: &ScanOptions) -> RepoReport {
+    check_repository(path, opts)
+        .unwrap_or_else(|e| RepoReport::from_error(path, describe_error(&e)))
+}
+
+/// Formats a git error together with its class and code, e.g. `... (Odb/NotFound)`
+pub fn describe_error(e: &git2::Error) -> String {
+    format!("{} ({:?}/{:?})", e.message(), e.class(), e.code())
+}
+
+fn check_repository(path: &Path, opts: &ScanOptions) -> Result<RepoReport, git2::Error> {
+    let mut report = RepoReport::new(path);
+    let mut repo = match Repository::open(path) {
+        Ok(repo) => repo,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(report),
+        Err(e) => return Err(e),
+    };
+
+    let remotes = repo.remotes()?;
+    let mut reachable = remotes.iter().flatten().collect::<Vec<_>>();
+
+    // Fetch first so every check below sees the current state of the remotes
+    if let Some(timeout) = opts.fetch_timeout {
+        reachable.retain(|name| match remote::fetch(&repo, name, timeout) {
+            Ok(()) => true,
+            Err(e) => {
+                report
+                    .unreachable_remotes
+                    .push(format!("{name} ({})", e.message()));
+                false
+            }
+        });
+    }
+
+    report.last_commit = checks::last_commit_time(&repo)?;
+    match checks::last_activity(&repo, report.last_commit) {
+        Ok(last_activity) => report.last_activity = last_activity,
+        Err(e) => report
+            .errors
+            .push(format!("could not read working tree: {e}")),
+    }
+
+    // Bare repositories have no working tree, only their refs can hold unpushed work
+    report.bare = repo.is_bare();
+    if !report.bare {
+        check_working_tree(&mut repo, &mut report)?;
+    }
+
+    match checks::disk_usage(&repo) {
+        Ok(usage) => {
+            report.size = Some(usage.total());
+            report.disk_usage = Some(usage);
+        }
+        Err(e) => report.errors.push(format!("could not measure size: {e}")),
+    }
+
+    report.worktrees = checks::worktree_problems(&repo)?;
+    report.checks.push(Check::Worktrees);
+
+    // Mirrors and plain bare clones have no remote-tracking branches to compare against, and they are
+    // often the last copy of a repository, so only asking the remotes can tell whether they are safe to delete
+    report.mirror = report.bare && checks::is_mirror(&repo)?;
+    let untracked_bare = report.bare
+        && !remotes.is_empty()
+        && repo.branches(Some(BranchType::Remote))?.next().is_none();
+    let verifying = opts.verify_remote && !reachable.is_empty();
+    if !untracked_bare {
+        report.unpushed = checks::unpushed_commits(&repo)?
+            .into_iter()
+            .map(|(branch, commits)| UnpushedBranch { branch, commits })
+            .collect();
+        report.checks.push(Check::Unpushed);
+    } else if !verifying {
+        report.needs_verification = true;
+        report.checks.push(Check::Unpushed);
+    }
+    report.detached_commits = checks::detached_head_commits(&repo)?;
+    report.checks.push(Check::DetachedHead);
+
+    // A remote-tracking branch can point at commits the server has lost since, e.g. after a force-push
+    let mut advertised = Vec::new();
+    if verifying {
+        for name in reachable {
+            match remote::advertised_oids(&repo, name) {
+                Ok(oids) => advertised.extend(oids),
+                Err(e) => report
+                    .unreachable_remotes
+                    .push(format!("{name} ({})", e.message())),
+            }
+        }
+
+        // A branch may only be on a remote that could not be listed, so that is all that gets reported
+        if report.unreachable_remotes.is_empty() {
+            report.unverified = checks::unverified_branches(&repo, &advertised)?
+                .into_iter()
+                .map(|(branch, commits)| UnpushedBranch { branch, commits })
+                .collect();
+            report.checks.push(Check::Verified);
+        }
+    }
+
+    if opts.all_refs {
+        report.unpushed_refs = checks::unpushed_other_refs(&repo, &advertised)?
+            .into_iter()
+            .map(|(reference, commits)| UnpushedRef { reference, commits })
+            .collect();
+        report.checks.push(Check::OtherRefs);
+    }
+
+    if let Some(window) = opts.reflog_window {
+        // A window reaching back further than chrono can represent covers the whole reflog
+        let since = TimeDelta::from_std(window)
+            .ok()
+            .and_then(|window| Utc::now().checked_sub_signed(window))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+        report.orphaned_commits = checks::orphaned_commits(&repo, since)?;
+        report.checks.push(Check::Reflog);
+    }
+
+    if opts.require_upstream {
+        report.unpublished_branches = checks::unpublished_branches(&repo)?;
+        report.checks.push(Check::Upstream);
+    }
+
+    if opts.check_tags {
+        match checks::local_only_tags(&repo) {
+            Ok(tags) => {
+                report.local_only_tags = tags;
+                report.checks.push(Check::Tags);
+            }
+            Err(e) => report.errors.push(format!(
+                "could not list remote tags: {}",
+                describe_error(&e)
+            )),
+        }
+    }
+
+    if !report.bare {
+        report.precious = checks::precious_files(&repo, &opts.precious)?;
+    }
+
+    if opts.artifacts {
+        for path in checks::artifact_dirs(&repo)? {
+            match walk::dir_size(&path, false) {
+                Ok(size) => report.artifacts.push(Artifact { path, size }),
+                Err(e) => report
+                    .errors
+                    .push(format!("could not measure {}: {e}", path.display())),
+            }
+        }
+    }
+
+    if !remotes.is_empty() && (opts.fetch_timeout.is_some() || opts.verify_remote) {
+        report.checks.push(Check::Remotes);
+    }
+
+    Ok(report.finish())
+}
+
+/// Runs the checks that only make sense for repositories with a working tree
+fn check_working_tree(repo: &mut Repository, report: &mut RepoReport) -> Result<(), git2::Error> {
+    let mut status_opts = checks::change_status_options();
+    for entry in repo.statuses(Some(&mut status_opts))?.iter() {
+        let file = String::from_utf8_lossy(entry.path_bytes()).into_owned();
+        if entry.status() == Status::WT_NEW {
+            report.untracked.push(file);
+        } else {
+            report.modified.push(file);
+        }
+    }
+    report.checks.push(Check::Changes);
+
+    // Stashes live outside of any branch and would be lost for good
+    report.stashes = checks::stash_count(repo)?;
+    report.checks.push(Check::Stashes);
+
+    // The real state of a rebase lives in the git directory, the working tree can look clean
+    report.operation = checks::operation_in_progress(repo).map(str::to_string);
+    report.checks.push(Check::Operation);
+
+    report.submodules = checks::submodule_problems(repo)?;
+    report.checks.push(Check::Submodules);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn options() -> ScanOptions {
+        ScanOptions {
+            require_upstream: false,
+            check_tags: false,
+            precious: GlobSet::empty(),
+            artifacts: false,
+            all_refs: false,
+            reflog_window: None,
+            fetch_timeout: None,
+            verify_remote: false,
+        }
+    }
+
+    fn verify_options() -> ScanOptions {
+        ScanOptions {
+            verify_remote: true,
+            ..options()
+        }
+    }
+
+    #[test]
+    fn pushed_repository_is_clean() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let path = dir.path().join("repo");
+        testutil::pushed_repo(&path, &url);
+
+        let report = scan_directory(&path, &verify_options());
+        assert_eq!(report.classification, Classification::Clean);
+    }
+
+    #[test]
+    fn commits_lost_on_the_remote_are_unpushed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let path = dir.path().join("repo");
+        let repo = testutil::pushed_repo(&path, &url);
+        let first = repo.head().unwrap().target().unwrap();
+        testutil::commit(&repo, "second");
+        testutil::push(&repo);
+
+        let branch = testutil::current_branch(&repo);
+        server
+            .reference(&format!("refs/heads/{branch}"), first, true, "force-push")
+            .unwrap();
+
+        let report = scan_directory(&path, &verify_options());
+        assert_eq!(report.classification, Classification::Unpushed);
+        assert_eq!(report.unverified.len(), 1);
+        assert!(report.unpushed.is_empty());
+    }
+
+    #[test]
+    fn unreachable_remote_is_reported_on_its_own() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let path = dir.path().join("repo");
+        let repo = testutil::pushed_repo(&path, &url);
+        let missing = format!("file://{}", dir.path().join("missing.git").display());
+        repo.remote_set_url("origin", &missing).unwrap();
+
+        let report = scan_directory(&path, &verify_options());
+        assert_eq!(report.classification, Classification::RemoteUnreachable);
+        assert!(report.unverified.is_empty());
+        assert_eq!(report.unreachable_remotes.len(), 1);
+    }
+
+    #[test]
+    fn fetch_leaves_the_branches_of_a_mirror_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let repo = testutil::pushed_repo(&dir.path().join("repo"), &url);
+        let head = repo.head().unwrap().target().unwrap();
+        server
+            .reference("refs/heads/feature", head, false, "")
+            .unwrap();
+
+        let path = dir.path().join("mirror.git");
+        let mirror = testutil::mirror(&path, &url);
+        server
+            .find_reference("refs/heads/feature")
+            .unwrap()
+            .delete()
+            .unwrap();
+        mirror
+            .reference("refs/heads/onlyhere", head, false, "")
+            .unwrap();
+
+        let opts = ScanOptions {
+            fetch_timeout: Some(Duration::from_secs(10)),
+            ..options()
+        };
+        scan_directory(&path, &opts);
+        assert!(mirror.find_reference("refs/heads/feature").is_ok());
+        assert!(mirror.find_reference("refs/heads/onlyhere").is_ok());
+    }
+
+    #[test]
+    fn mirror_is_kept_without_verify_remote() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        testutil::pushed_repo(&dir.path().join("repo"), &url);
+        let path = dir.path().join("mirror.git");
+        testutil::mirror(&path, &url);
+
+        let report = scan_directory(&path, &options());
+        assert!(report.mirror);
+        assert!(report.needs_verification);
+        assert_eq!(report.classification, Classification::Unpushed);
+    }
+
+    #[test]
+    fn mirror_is_clean_once_its_remote_is_verified() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        testutil::pushed_repo(&dir.path().join("repo"), &url);
+        let path = dir.path().join("mirror.git");
+        testutil::mirror(&path, &url);
+
+        let report = scan_directory(&path, &verify_options());
+        assert!(!report.needs_verification);
+        assert_eq!(report.classification, Classification::Clean);
+    }
+
+    #[test]
+    fn mirror_with_commits_the_remote_lacks_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, url) = testutil::bare_remote(&dir.path().join("remote.git"));
+        let repo = testutil::pushed_repo(&dir.path().join("repo"), &url);
+        let first = repo.head().unwrap().target().unwrap();
+        testutil::commit(&repo, "second");
+        testutil::push(&repo);
+        let path = dir.path().join("mirror.git");
+        testutil::mirror(&path, &url);
+
+        // The upstream moves away, the second commit now only exists in the mirror
+        let branch = testutil::current_branch(&repo);
+        server
+            .reference(&format!("refs/heads/{branch}"), first, true, "force-push")
+            .unwrap();
+
+        let report = scan_directory(&path, &verify_options());
+        assert_eq!(report.unverified.len(), 1);
+        assert_eq!(report.classification, Classification::Unpushed);
+    }
+}
@@ -0,0 +1,74 @@
+//! Helpers to build repositories with a local bare remote in tests
+
+use std::path::Path;
+
+use git2::{Oid, Repository, Signature};
+
+/// Creates an empty bare repository and returns it together with its `file://` URL
+pub fn bare_remote(path: &Path) -> (Repository, String) {
+    let remote = Repository::init_bare(path).unwrap();
+    let url = format!("file://{}", path.display());
+
+    (remote, url)
+}
+
+/// Creates a repository with one commit that is pushed to and fetched from `origin`
+pub fn pushed_repo(path: &Path, url: &str) -> Repository {
+    let repo = Repository::init(path).unwrap();
+    commit(&repo, "initial");
+    repo.remote("origin", url).unwrap();
+    push(&repo);
+
+    repo
+}
+
+/// Commits an empty change on top of HEAD
+pub fn commit(repo: &Repository, message: &str) -> Oid {
+    let signature = Signature::now("Test", "test@example.com").unwrap();
+    let tree = repo
+        .find_tree(repo.index().unwrap().write_tree().unwrap())
+        .unwrap();
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        parent.as_slice().iter().collect::<Vec<_>>().as_slice(),
+    )
+    .unwrap()
+}
+
+/// Pushes the current branch to `origin` and fetches it back into the remote-tracking branch
+pub fn push(repo: &Repository) {
+    let branch = current_branch(repo);
+    let mut remote = repo.find_remote("origin").unwrap();
+    remote
+        .push(&[format!("refs/heads/{branch}:refs/heads/{branch}")], None)
+        .unwrap();
+    remote.fetch::<&str>(&[], None, None).unwrap();
+}
+
+/// The name of the checked out branch, which depends on `init.defaultBranch`
+pub fn current_branch(repo: &Repository) -> String {
+    repo.head().unwrap().shorthand().unwrap().to_string()
+}
+
+/// Creates a bare mirror of `url`, like `git clone --mirror` does
+pub fn mirror(path: &Path, url: &str) -> Repository {
+    let repo = Repository::init_bare(path).unwrap();
+    repo.remote_with_fetch("origin", url, "+refs/*:refs/*")
+        .unwrap();
+    repo.config()
+        .unwrap()
+        .set_bool("remote.origin.mirror", true)
+        .unwrap();
+    repo.find_remote("origin")
+        .unwrap()
+        .fetch::<&str>(&[], None, None)
+        .unwrap();
+
+    repo
+}
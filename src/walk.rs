@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use tokio::fs;
+
+/// Controls how far the directory tree below the scan root is walked
+#[derive(Debug, Clone, Copy)]
+pub struct WalkOptions {
+    /// Number of directory levels below the root that are visited
+    pub max_depth: usize,
+    /// Keep descending into directories that are git repositories themselves
+    pub nested: bool,
+}
+
+/// Every directory found below the scan root
+#[derive(Debug, Default)]
+pub struct Walk {
+    pub directories: Vec<PathBuf>,
+    /// Directories that were found but could not be read
+    pub errors: HashMap<PathBuf, io::Error>,
+}
+
+/// Collects every directory below `root`, up to `max_depth` levels deep.
+///
+/// A directory containing a `.git` entry is reported but not descended into,
+/// unless nested scanning is enabled. `.git` directories and the insides of
+/// bare repositories are never visited.
+/// Only failing to read `root` itself is an error, every other unreadable
+/// directory is recorded in [`Walk::errors`].
+pub async fn find_directories(root: &Path, opts: WalkOptions) -> io::Result<Walk> {
+    let mut walk = Walk::default();
+    let mut pending = vec![(root.to_path_buf(), 0)];
+
+    while let Some((dir, depth)) = pending.pop() {
+        if depth >= opts.max_depth {
+            continue;
+        }
+
+        let res = read_children(&dir, depth, opts, &mut walk.directories, &mut pending).await;
+        if let Err(e) = res {
+            if depth == 0 {
+                return Err(e);
+            }
+            walk.errors.insert(dir, e);
+        }
+    }
+
+    walk.directories.sort();
+    Ok(walk)
+}
+
+async fn read_children(
+    dir: &Path,
+    depth: usize,
+    opts: WalkOptions,
+    found: &mut Vec<PathBuf>,
+    pending: &mut Vec<(PathBuf, usize)>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if !path.is_dir() || entry.file_name() == ".git" {
+            continue;
+        }
+
+        // Never follow symlinks further down, they can easily form cycles
+        let is_symlink = entry.file_type().await?.is_symlink();
+        // A bare repository only holds git internals, there is nothing nested to find in there
+        let descend = if is_bare_repo(&path) {
+            false
+        } else {
+            !path.join(".git").exists() || opts.nested
+        };
+        if !is_symlink && descend {
+            pending.push((path.clone(), depth + 1));
+        }
+
+        found.push(path);
+    }
+
+    Ok(())
+}
+
+/// Checks for the layout of a bare repository, like `foo.git` created by `git clone --bare`
+fn is_bare_repo(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+/// Adds up the size of every file below `path`, without following symlinks
+///
+/// This blocks, so it is meant to be called from the scan workers. Entries
+/// named `.git` directly inside `path` are skipped when `skip_git` is set.
+pub fn dir_size(path: &Path, skip_git: bool) -> io::Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+    let mut pending = vec![path.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            if skip_git && dir == path && entry.file_name() == ".git" {
+                continue;
+            }
+
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Repository;
+
+    use super::*;
+
+    #[test]
+    fn is_bare_repo_detects_bare_layout_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let bare = dir.path().join("bare.git");
+        Repository::init_bare(&bare).unwrap();
+        let work = dir.path().join("work");
+        Repository::init(&work).unwrap();
+        let plain = dir.path().join("plain");
+        std::fs::create_dir_all(plain.join("objects")).unwrap();
+        std::fs::write(plain.join("HEAD"), "not a repository").unwrap();
+
+        assert!(is_bare_repo(&bare));
+        assert!(!is_bare_repo(&work));
+        assert!(is_bare_repo(&work.join(".git")));
+        assert!(!is_bare_repo(&plain));
+    }
+
+    fn relative(root: &Path, walk: &Walk) -> Vec<String> {
+        walk.directories
+            .iter()
+            .map(|dir| dir.strip_prefix(root).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn stops_at_max_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b/c")).unwrap();
+        std::fs::write(dir.path().join("file"), "").unwrap();
+
+        let opts = WalkOptions {
+            max_depth: 2,
+            nested: false,
+        };
+        let walk = find_directories(dir.path(), opts).await.unwrap();
+        assert_eq!(relative(dir.path(), &walk), ["a", "a/b"]);
+    }
+
+    #[tokio::test]
+    async fn skips_git_internals_and_only_enters_repositories_when_nested() {
+        let dir = tempfile::tempdir().unwrap();
+        Repository::init(dir.path().join("repo")).unwrap();
+        std::fs::create_dir_all(dir.path().join("repo/inner")).unwrap();
+        Repository::init_bare(dir.path().join("bare.git")).unwrap();
+
+        let opts = WalkOptions {
+            max_depth: 3,
+            nested: false,
+        };
+        let walk = find_directories(dir.path(), opts).await.unwrap();
+        assert_eq!(relative(dir.path(), &walk), ["bare.git", "repo"]);
+
+        let opts = WalkOptions {
+            nested: true,
+            ..opts
+        };
+        let walk = find_directories(dir.path(), opts).await.unwrap();
+        assert_eq!(
+            relative(dir.path(), &walk),
+            ["bare.git", "repo", "repo/inner"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reports_but_never_follows_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("a/loop")).unwrap();
+
+        let opts = WalkOptions {
+            max_depth: 5,
+            nested: false,
+        };
+        let walk = find_directories(dir.path(), opts).await.unwrap();
+        assert_eq!(relative(dir.path(), &walk), ["a", "a/b", "a/loop"]);
+    }
+
+    #[tokio::test]
+    async fn fails_only_for_a_missing_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = WalkOptions {
+            max_depth: 1,
+            nested: false,
+        };
+        assert!(
+            find_directories(&dir.path().join("missing"), opts)
+                .await
+                .is_err()
+        );
+    }
+}